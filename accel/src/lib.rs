@@ -1,11 +1,15 @@
 use blake2::digest::{Update, VariableOutput};
 use blake2::Blake2bVar;
 use pyo3::prelude::*;
+use std::sync::OnceLock;
 
 const DEFAULT_SIMHASH_MAX_TOKENS: usize = 20000;
 const DEFAULT_MINHASH_MAX_SHINGLES: usize = 20000;
 const PRIME32: u64 = 4294967311;
 const ADLER_MOD: u32 = 65521;
+/// Mersenne prime 2^61-1, used as the modulus for the 64-bit shingle hash
+/// permutations in `minhash_signature_with_coeffs64`.
+const PRIME61: u64 = (1u64 << 61) - 1;
 
 fn is_ascii_alpha(b: u8) -> bool {
     (b'A'..=b'Z').contains(&b) || (b'a'..=b'z').contains(&b)
@@ -134,6 +138,291 @@ fn simhash64(text: &str, max_tokens: Option<usize>) -> PyResult<u64> {
     Ok(out)
 }
 
+/// Lowercased, stopword-filtered tokens (len >= 4) from `text`, in order,
+/// capped at `limit` tokens. Tokenizer behind `simhash64_weighted`; it applies
+/// the same token rules as the inline loop in `simhash64`, which does not
+/// call it.
+fn simhash_tokens(text: &str, limit: usize) -> Vec<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0usize;
+
+    while i < bytes.len() && tokens.len() < limit {
+        let b = bytes[i];
+        if is_ascii_alpha(b) {
+            let mut token: Vec<u8> = Vec::new();
+            token.push(b.to_ascii_lowercase());
+            i += 1;
+            while i < bytes.len() {
+                let b2 = bytes[i];
+                if is_ascii_alnum_or_underscore(b2) {
+                    token.push(b2.to_ascii_lowercase());
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            if token.len() >= 4 && !is_stopword(&token) {
+                tokens.push(token);
+            }
+        } else {
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Weighted variant of `simhash64`: accumulates each *distinct* qualifying
+/// token into the 64 bit-counters once, with a weight `w(token)` instead of
+/// `±1`, so a token that recurs (or is otherwise marked discriminative)
+/// outweighs boilerplate that only appears once. `w` defaults to the token's
+/// term frequency within `text`; passing `idf` (a token -> weight map, e.g.
+/// inverse document frequencies computed by the caller) overrides it
+/// per-token, falling back to a neutral weight of `1.0` for tokens missing
+/// from the map.
+#[pyfunction]
+#[pyo3(signature = (text, idf=None, max_tokens=None))]
+fn simhash64_weighted(
+    text: &str,
+    idf: Option<std::collections::HashMap<String, f64>>,
+    max_tokens: Option<usize>,
+) -> PyResult<u64> {
+    let limit = max_tokens.unwrap_or(DEFAULT_SIMHASH_MAX_TOKENS);
+    if limit == 0 {
+        return Ok(0);
+    }
+
+    let tokens = simhash_tokens(text, limit);
+
+    let mut term_freq: std::collections::HashMap<&[u8], usize> = std::collections::HashMap::new();
+    for token in &tokens {
+        *term_freq.entry(token.as_slice()).or_insert(0) += 1;
+    }
+
+    let mut v = [0f64; 64];
+    for (&token, &count) in &term_freq {
+        let weight = match &idf {
+            Some(map) => {
+                let key = String::from_utf8_lossy(token);
+                map.get(key.as_ref()).copied().unwrap_or(1.0)
+            }
+            None => count as f64,
+        };
+
+        let h = token_hash64(token);
+        for bit in 0..64 {
+            if (h >> bit) & 1 == 1 {
+                v[bit] += weight;
+            } else {
+                v[bit] -= weight;
+            }
+        }
+    }
+
+    let mut out = 0u64;
+    for (idx, val) in v.iter().enumerate() {
+        if *val > 0.0 {
+            out |= 1u64 << idx;
+        }
+    }
+    Ok(out)
+}
+
+/// Bit difference between two SimHash fingerprints.
+#[pyfunction]
+fn simhash_hamming(a: u64, b: u64) -> PyResult<u32> {
+    Ok((a ^ b).count_ones())
+}
+
+/// Similarity in `[0.0, 1.0]` derived from `simhash_hamming`, where `1.0`
+/// means identical fingerprints and `0.0` means maximally different.
+#[pyfunction]
+fn simhash_similarity(a: u64, b: u64) -> PyResult<f64> {
+    Ok(1.0 - (a ^ b).count_ones() as f64 / 64.0)
+}
+
+/// Masks for the `(max_distance + 1)`-way bit-segment split used by
+/// `simhash_query`'s pigeonhole pre-filter: any fingerprint within
+/// `max_distance` bits of the query must match exactly in at least one
+/// segment, since the segments partition all 64 bits and there are more
+/// segments than allowed differing bits.
+fn segment_masks(segments: usize) -> Vec<u64> {
+    let base_width = 64 / segments;
+    let remainder = 64 % segments;
+    let mut masks = Vec::with_capacity(segments);
+    let mut shift = 0u32;
+    for s in 0..segments {
+        let width = if s < remainder { base_width + 1 } else { base_width };
+        let mask = (if width >= 64 { u64::MAX } else { (1u64 << width) - 1 }) << shift;
+        masks.push(mask);
+        shift += width as u32;
+    }
+    masks
+}
+
+/// Indices of `candidates` within `max_distance` Hamming bits of `query`.
+///
+/// Builds one hash index per segment, mapping each candidate's bits in that
+/// segment to the indices sharing them, the classic multi-index hashing
+/// trick for Hamming search: by the pigeonhole principle, any fingerprint
+/// within `max_distance` bits of the query must agree exactly with it in at
+/// least one of the `max_distance + 1` segments, so only candidates that
+/// land in one of the query's own segment buckets are popcount-checked,
+/// instead of computing a popcount against every candidate. Building the
+/// indexes still walks every candidate once per segment.
+#[pyfunction]
+fn simhash_query(query: u64, candidates: Vec<u64>, max_distance: u32) -> PyResult<Vec<usize>> {
+    if max_distance >= 64 {
+        return Ok((0..candidates.len()).collect());
+    }
+
+    let masks = segment_masks((max_distance + 1) as usize);
+
+    let mut segment_indexes: Vec<std::collections::HashMap<u64, Vec<usize>>> =
+        vec![std::collections::HashMap::new(); masks.len()];
+    for (idx, cand) in candidates.iter().enumerate() {
+        for (segment_idx, &mask) in masks.iter().enumerate() {
+            segment_indexes[segment_idx]
+                .entry(cand & mask)
+                .or_default()
+                .push(idx);
+        }
+    }
+
+    let mut shortlist = std::collections::BTreeSet::new();
+    for (segment_idx, &mask) in masks.iter().enumerate() {
+        if let Some(bucket) = segment_indexes[segment_idx].get(&(query & mask)) {
+            shortlist.extend(bucket.iter().copied());
+        }
+    }
+
+    Ok(shortlist
+        .into_iter()
+        .filter(|&idx| (query ^ candidates[idx]).count_ones() <= max_distance)
+        .collect())
+}
+
+fn seeded_shingle_hash64(seed: u64, gram: &[u8]) -> u64 {
+    let mut hasher = Blake2bVar::new(8).expect("blake2b init");
+    hasher.update(&seed.to_le_bytes());
+    hasher.update(gram);
+    let mut out = [0u8; 8];
+    hasher.finalize_variable(&mut out).expect("blake2b finalize");
+    u64::from_le_bytes(out)
+}
+
+/// Scaled MinHash ("FracMinHash") over the k-shingles of `text`.
+///
+/// Unlike `minhash_signature_with_coeffs`, which always returns a fixed-size
+/// signature, this retains every shingle hash `h` with `h <= u64::MAX / scaled`,
+/// giving a variable-size bottom sketch that can be exactly unioned/intersected
+/// across documents of very different sizes (see `fracminhash_compare`).
+#[pyfunction]
+#[pyo3(signature = (text, k, scaled, seed, max_shingles=None))]
+fn fracminhash_signature(
+    text: &str,
+    k: usize,
+    scaled: u64,
+    seed: u64,
+    max_shingles: Option<usize>,
+) -> PyResult<Vec<u64>> {
+    if k == 0 || scaled == 0 {
+        return Ok(Vec::new());
+    }
+
+    let max_shingles = match max_shingles {
+        None => Some(DEFAULT_MINHASH_MAX_SHINGLES),
+        Some(0) => None,
+        Some(val) => Some(val),
+    };
+
+    let text_char_len = text.chars().count();
+    if text_char_len < k {
+        return Ok(Vec::new());
+    }
+
+    let mut truncated: Option<String> = None;
+    if let Some(limit) = max_shingles {
+        let char_limit = limit.saturating_add(k.saturating_sub(1));
+        if text_char_len > char_limit {
+            truncated = Some(text.chars().take(char_limit).collect::<String>());
+        }
+    }
+
+    let text_ref = truncated.as_deref().unwrap_or(text);
+    let mut bytes = text_ref.as_bytes();
+    if bytes.len() < k {
+        return Ok(Vec::new());
+    }
+
+    if let Some(limit) = max_shingles {
+        let total = bytes.len().saturating_sub(k).saturating_add(1);
+        if total > limit {
+            let byte_limit = limit.saturating_add(k.saturating_sub(1));
+            if bytes.len() > byte_limit {
+                bytes = &bytes[..byte_limit];
+            }
+        }
+    }
+
+    let threshold = u64::MAX / scaled;
+    let mut retained = std::collections::BTreeSet::new();
+    if bytes.len() >= k {
+        for i in 0..=bytes.len() - k {
+            let gram = &bytes[i..i + k];
+            if !gram.iter().any(|&c| c > 32) {
+                continue;
+            }
+            let h = seeded_shingle_hash64(seed, gram);
+            if h <= threshold {
+                retained.insert(h);
+            }
+        }
+    }
+
+    Ok(retained.into_iter().collect())
+}
+
+/// Jaccard similarity and both containment scores between two FracMinHash
+/// sketches produced by `fracminhash_signature` (assumed sorted ascending).
+///
+/// Returns `(jaccard, containment_ab, containment_ba)` where `containment_ab`
+/// is `|A ∩ B| / |A|` and `containment_ba` is `|A ∩ B| / |B|`, each computed
+/// via a single sorted merge-join over the retained hash sets.
+#[pyfunction]
+fn fracminhash_compare(sig_a: Vec<u64>, sig_b: Vec<u64>) -> PyResult<(f64, f64, f64)> {
+    if sig_a.is_empty() || sig_b.is_empty() {
+        return Ok((0.0, 0.0, 0.0));
+    }
+
+    let mut i = 0usize;
+    let mut j = 0usize;
+    let mut intersection = 0usize;
+    while i < sig_a.len() && j < sig_b.len() {
+        match sig_a[i].cmp(&sig_b[j]) {
+            std::cmp::Ordering::Equal => {
+                intersection += 1;
+                i += 1;
+                j += 1;
+            }
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+        }
+    }
+
+    let union = sig_a.len() + sig_b.len() - intersection;
+    let jaccard = intersection as f64 / union as f64;
+    let containment_ab = intersection as f64 / sig_a.len() as f64;
+    let containment_ba = intersection as f64 / sig_b.len() as f64;
+    Ok((jaccard, containment_ab, containment_ba))
+}
+
+/// Adler-32 shingle hash. Only 32 bits wide, so it collides heavily on short
+/// byte windows and biases every min permutation upward; kept only so
+/// `minhash_signature_with_coeffs` keeps producing its historical output.
+/// New code should hash shingles with `shingle_hash64` instead (see
+/// `minhash_signature_with_coeffs64`).
 fn adler32(data: &[u8]) -> u32 {
     let mut s1: u32 = 1;
     let mut s2: u32 = 0;
@@ -144,6 +433,18 @@ fn adler32(data: &[u8]) -> u32 {
     (s2 << 16) | s1
 }
 
+fn shingle_hash64(gram: &[u8]) -> u64 {
+    let mut hasher = Blake2bVar::new(8).expect("blake2b init");
+    hasher.update(gram);
+    let mut out = [0u8; 8];
+    hasher.finalize_variable(&mut out).expect("blake2b finalize");
+    u64::from_le_bytes(out)
+}
+
+/// Deprecated: hashes shingles with `adler32`, a 32-bit hash that collides
+/// heavily on short byte windows and biases every min permutation upward.
+/// Kept unchanged so existing callers keep their historical signatures; new
+/// code should use `minhash_signature_with_coeffs64`.
 #[pyfunction]
 #[pyo3(signature = (text, k, coeffs, max_shingles=None))]
 fn minhash_signature_with_coeffs(
@@ -220,11 +521,251 @@ fn minhash_signature_with_coeffs(
     Ok(sig)
 }
 
+/// Same bank-of-permutations MinHash as `minhash_signature_with_coeffs`, but
+/// hashes shingles with `shingle_hash64` (the `Blake2bVar`-to-`u64` path used
+/// by `token_hash64`) instead of `adler32`, and permutes mod the Mersenne
+/// prime `PRIME61` (2^61-1, computed in 128-bit arithmetic) instead of
+/// `PRIME32`. This removes the systematic
+/// upward bias `adler32` collisions introduce into Jaccard estimates without
+/// changing the shape of the returned signature.
+#[pyfunction]
+#[pyo3(signature = (text, k, coeffs, max_shingles=None))]
+fn minhash_signature_with_coeffs64(
+    text: &str,
+    k: usize,
+    coeffs: Vec<(u64, u64)>,
+    max_shingles: Option<usize>,
+) -> PyResult<Vec<u64>> {
+    if k == 0 {
+        return Ok(vec![u64::MAX; coeffs.len()]);
+    }
+
+    let max_shingles = match max_shingles {
+        None => Some(DEFAULT_MINHASH_MAX_SHINGLES),
+        Some(0) => None,
+        Some(val) => Some(val),
+    };
+
+    let text_char_len = text.chars().count();
+    if text_char_len < k {
+        return Ok(vec![u64::MAX; coeffs.len()]);
+    }
+
+    let mut truncated: Option<String> = None;
+    if let Some(limit) = max_shingles {
+        let char_limit = limit.saturating_add(k.saturating_sub(1));
+        if text_char_len > char_limit {
+            truncated = Some(text.chars().take(char_limit).collect::<String>());
+        }
+    }
+
+    let text_ref = truncated.as_deref().unwrap_or(text);
+    let mut bytes = text_ref.as_bytes();
+    if bytes.len() < k {
+        return Ok(vec![u64::MAX; coeffs.len()]);
+    }
+
+    if let Some(limit) = max_shingles {
+        let total = bytes.len().saturating_sub(k).saturating_add(1);
+        if total > limit {
+            let byte_limit = limit.saturating_add(k.saturating_sub(1));
+            if bytes.len() > byte_limit {
+                bytes = &bytes[..byte_limit];
+            }
+        }
+    }
+
+    let mut shingles = std::collections::HashSet::new();
+    if bytes.len() >= k {
+        for i in 0..=bytes.len() - k {
+            let gram = &bytes[i..i + k];
+            if !gram.iter().any(|&c| c > 32) {
+                continue;
+            }
+            shingles.insert(shingle_hash64(gram));
+        }
+    }
+
+    if shingles.is_empty() {
+        return Ok(vec![u64::MAX; coeffs.len()]);
+    }
+
+    let mut sig = vec![u64::MAX; coeffs.len()];
+    for x in shingles {
+        for (idx, (a, b)) in coeffs.iter().enumerate() {
+            let v = ((*a as u128 * x as u128 + *b as u128) % PRIME61 as u128) as u64;
+            if v < sig[idx] {
+                sig[idx] = v;
+            }
+        }
+    }
+
+    Ok(sig)
+}
+
+fn band_bucket_key(rows: &[u64]) -> u64 {
+    let mut hasher = Blake2bVar::new(8).expect("blake2b init");
+    for row in rows {
+        hasher.update(&row.to_le_bytes());
+    }
+    let mut out = [0u8; 8];
+    hasher.finalize_variable(&mut out).expect("blake2b finalize");
+    u64::from_le_bytes(out)
+}
+
+/// Partition a MinHash `signature` into `num_bands` bands of `r = n / num_bands`
+/// rows each and hash each band's rows together into a 64-bit bucket key.
+///
+/// Two documents are near-duplicate candidates iff any of their band keys
+/// collide, with collision probability ≈ 1-(1-s^r)^b for Jaccard similarity
+/// `s` — the classic LSH S-curve. Use `recommend_bands` to pick `num_bands`
+/// for a target similarity threshold. Trailing rows that don't fill a full
+/// band (when `num_bands` doesn't evenly divide the signature length) are
+/// dropped.
+#[pyfunction]
+fn minhash_lsh_bands(signature: Vec<u64>, num_bands: usize) -> PyResult<Vec<u64>> {
+    if num_bands == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "num_bands must be greater than zero",
+        ));
+    }
+
+    let rows_per_band = signature.len() / num_bands;
+    if rows_per_band == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "signature is shorter than num_bands",
+        ));
+    }
+
+    let mut bands = Vec::with_capacity(num_bands);
+    for band in signature.chunks(rows_per_band).take(num_bands) {
+        bands.push(band_bucket_key(band));
+    }
+    Ok(bands)
+}
+
+/// Search the `(b, r)` factorizations of `n` (i.e. `b * r == n`, so every
+/// signature row ends up in exactly one band) for the pair whose LSH S-curve
+/// midpoint `(1/b)^(1/r)` is closest to `target_threshold`, returning
+/// `(b, r)`. Intended to pick `num_bands` for `minhash_lsh_bands` given a
+/// desired near-duplicate similarity cutoff.
+#[pyfunction]
+fn recommend_bands(n: usize, target_threshold: f64) -> PyResult<(usize, usize)> {
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "n must be greater than zero",
+        ));
+    }
+
+    let mut best: Option<(usize, usize, f64)> = None;
+    for b in 1..=n {
+        if !n.is_multiple_of(b) {
+            continue;
+        }
+        let r = n / b;
+        let midpoint = (1.0 / b as f64).powf(1.0 / r as f64);
+        let distance = (midpoint - target_threshold).abs();
+        if best.is_none_or(|(_, _, best_distance)| distance < best_distance) {
+            best = Some((b, r, distance));
+        }
+    }
+
+    let (b, r, _) = best.expect("n >= 1 guarantees at least one (b, r) divisor pair");
+    Ok((b, r))
+}
+
+/// 256-entry table of pseudo-random 64-bit values used by the gear hash in
+/// `content_defined_chunks`, one per possible byte value. Derived from
+/// `Blake2bVar` rather than hardcoded so there's no giant literal table to
+/// maintain; it only needs to be well-mixed, not cryptographically secret.
+fn gear_table() -> &'static [u64; 256] {
+    static TABLE: OnceLock<[u64; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0u64; 256];
+        for (i, slot) in table.iter_mut().enumerate() {
+            *slot = seeded_shingle_hash64(0x6765_6172, &[i as u8]);
+        }
+        table
+    })
+}
+
+/// Content-defined chunk boundaries over `data` using FastCDC-style gear
+/// hashing with the normalized-chunking trick: a stricter (more-bits-set)
+/// mask is used below `avg_size` and a looser one above it, tightening the
+/// chunk size distribution around `avg_size` while keeping each chunk
+/// between `min_size` and `max_size` bytes; the final chunk holds whatever
+/// remains of `data` and may be shorter than `min_size`. Returns `(offset, length)` pairs
+/// covering `data` so callers can hash each chunk to deduplicate identical
+/// blocks across file versions.
+#[pyfunction]
+fn content_defined_chunks(
+    data: Vec<u8>,
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+) -> PyResult<Vec<(usize, usize)>> {
+    if min_size == 0 || avg_size < min_size || max_size < avg_size {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "require 0 < min_size <= avg_size <= max_size",
+        ));
+    }
+
+    let bits = (avg_size as f64).log2().round() as u32;
+    let small_bits = (bits + 1).min(63);
+    let large_bits = bits.saturating_sub(1).clamp(1, 63);
+    let mask_small: u64 = (1u64 << small_bits) - 1;
+    let mask_large: u64 = (1u64 << large_bits) - 1;
+
+    let gear = gear_table();
+    let mut chunks = Vec::new();
+    let mut offset = 0usize;
+    let n = data.len();
+
+    while offset < n {
+        let chunk_max = (offset + max_size).min(n);
+        let skip_to = (offset + min_size).min(chunk_max);
+        let avg_point = (offset + avg_size).min(chunk_max);
+
+        let mut h = 0u64;
+        for &b in &data[offset..skip_to] {
+            h = (h << 1).wrapping_add(gear[b as usize]);
+        }
+
+        let mut cut = None;
+        let mut i = skip_to;
+        while i < chunk_max {
+            h = (h << 1).wrapping_add(gear[data[i] as usize]);
+            let mask = if i < avg_point { mask_small } else { mask_large };
+            if h & mask == 0 {
+                cut = Some(i + 1);
+                break;
+            }
+            i += 1;
+        }
+
+        let end = cut.unwrap_or(chunk_max);
+        chunks.push((offset, end - offset));
+        offset = end;
+    }
+
+    Ok(chunks)
+}
+
 #[pymodule]
 fn _native(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     let qc = PyModule::new(py, "qc")?;
     qc.add_function(wrap_pyfunction!(simhash64, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(simhash64_weighted, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(simhash_hamming, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(simhash_similarity, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(simhash_query, &qc)?)?;
     qc.add_function(wrap_pyfunction!(minhash_signature_with_coeffs, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(minhash_signature_with_coeffs64, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(fracminhash_signature, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(fracminhash_compare, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(minhash_lsh_bands, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(recommend_bands, &qc)?)?;
+    qc.add_function(wrap_pyfunction!(content_defined_chunks, &qc)?)?;
     m.add_submodule(&qc)?;
     Ok(())
 }